@@ -1,13 +1,20 @@
-use anyhow::anyhow;
 use std::str::FromStr;
 
 use hyper::body::Body;
 use hyper::{Request, Response, StatusCode, Uri};
 use hyper_tls::HttpsConnector;
+use subtle::ConstantTimeEq;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
 
-use crate::body::{body_to_string, string_to_body};
+use crate::body::string_to_body;
 use crate::secret_getter::SecretGetter;
 use crate::server::Server;
+use crate::signing::chunked_body::ChunkedSignedBody;
+use crate::signing::signing_functions::{
+    content_sha256_hex, parse_authorization_header, parse_rsa_signature, signing_key,
+    verify_rsa_signature, ALGORITHM_RSA, LONG_DATETIME, STREAMING_PAYLOAD, UNSIGNED_PAYLOAD,
+    X_AUTHORIZATION, X_CONTENT_SHA256, X_DATE, X_EXPIRES,
+};
 use crate::signing::{SignRequest, UrlSigner};
 
 fn bad_request() -> Response<Body> {
@@ -31,21 +38,96 @@ fn bad_gateway(e: impl Into<anyhow::Error>) -> Response<Body> {
         .unwrap()
 }
 
+// A leaked presigned URL must stop working once it expires, and a date too
+// far in the future is as suspicious as one that's already expired. Kept as
+// a free function of its inputs (rather than inlined into the `Server`
+// method below) so it can be unit-tested without a full `Server<T>`.
+fn validity_window_ok(
+    date: &PrimitiveDateTime,
+    expires: u32,
+    now: PrimitiveDateTime,
+    clock_skew: Duration,
+    max_expires: u32,
+) -> bool {
+    if expires > max_expires {
+        return false;
+    }
+
+    if *date > now + clock_skew {
+        return false;
+    }
+
+    now <= *date + Duration::seconds(expires as i64)
+}
+
 impl<T: SecretGetter> Server<T> {
-    fn parse_content_length<B>(req: &Request<B>) -> anyhow::Result<usize> {
-        let content_length = req
-            .headers()
-            .get("content-length")
-            .ok_or_else(|| anyhow!("Content-Length header not present"))?;
-        Ok(usize::from_str(content_length.to_str()?)?)
+    // `clock_skew` and `max_expires` are configured on `Server` so operators
+    // can tune how forgiving these checks are for their clients.
+    fn check_validity_window(&self, date: &PrimitiveDateTime, expires: u32) -> bool {
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+        validity_window_ok(date, expires, now, self.clock_skew, self.max_expires)
     }
 
     pub async fn route_gateway(&self, mut req: Request<Body>) -> hyper::Result<Response<Body>> {
-        let (mut to_sign, info) = match SignRequest::from_req(&req) {
+        let (mut to_sign, mut info) = match SignRequest::from_req(&req) {
             Ok((a, b)) => (a, b),
             Err(_) => return Ok(bad_request()),
         };
 
+        // Header-based clients carry their credential, signed-header list
+        // and signature in `Authorization`, and their date/expiry in plain
+        // headers, rather than the `X-Sup-*` query params `from_req`
+        // defaults to. Wire all of them in so the access-key id, the
+        // validity window, and the exact set of headers the client
+        // committed to all come from the same place the signature does.
+        if let Some(header) = req.headers().get(X_AUTHORIZATION) {
+            let header = match header.to_str() {
+                Ok(a) => a,
+                Err(_) => return Ok(bad_request()),
+            };
+            let (credential, signed_headers, signature) = match parse_authorization_header(header)
+            {
+                Ok(a) => a,
+                Err(_) => return Ok(bad_request()),
+            };
+
+            let Some((id, _scope)) = credential.split_once('/') else {
+                return Ok(bad_request());
+            };
+            info.id = id.to_string();
+            info.signature = signature;
+            to_sign.signed_headers = signed_headers
+                .split(';')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            let date = req
+                .headers()
+                .get(X_DATE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| PrimitiveDateTime::parse(v, LONG_DATETIME).ok());
+            let Some(date) = date else {
+                return Ok(bad_request());
+            };
+            to_sign.date = date;
+
+            let expires = req
+                .headers()
+                .get(X_EXPIRES)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u32>().ok());
+            let Some(expires) = expires else {
+                return Ok(bad_request());
+            };
+            to_sign.expires = expires;
+        }
+
+        if !self.check_validity_window(&to_sign.date, to_sign.expires) {
+            return Ok(bad_request());
+        }
+
         let secret = match self.secret_getter.get_secret(&info.id).await {
             Ok(a) => a,
             Err(e) => return Ok(internal_server(e)),
@@ -58,17 +140,34 @@ impl<T: SecretGetter> Server<T> {
         let signer = UrlSigner::new(&info.id, &secret, self.self_host.clone());
 
         if info.include_body {
-            let content_length = match Self::parse_content_length(&req) {
-                Ok(a) => a,
-                Err(_) => return Ok(bad_request()),
-            };
-            let (parts, body) = req.into_parts();
-            let body = match body_to_string(body, content_length).await {
-                Ok(a) => a,
-                Err(_) => return Ok(bad_request()),
-            };
-            to_sign.body = Some(body.clone());
-            req = Request::from_parts(parts, string_to_body(&body))
+            if info.streaming {
+                // The canonical request is signed against the literal
+                // placeholder, not the real body, so the seed signature can
+                // be verified before a single chunk has arrived.
+                to_sign.body = Some(STREAMING_PAYLOAD.to_string());
+            } else {
+                let declared_hash = req
+                    .headers()
+                    .get(X_CONTENT_SHA256)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+
+                let (parts, body) = req.into_parts();
+                let bytes = match hyper::body::to_bytes(body).await {
+                    Ok(a) => a,
+                    Err(_) => return Ok(bad_request()),
+                };
+
+                if declared_hash != UNSIGNED_PAYLOAD
+                    && declared_hash != content_sha256_hex(&bytes)
+                {
+                    return Ok(bad_request());
+                }
+
+                to_sign.body = Some(declared_hash);
+                req = Request::from_parts(parts, Body::from(bytes));
+            }
         }
 
         let Some(host) = to_sign.proxy_url.host() else {
@@ -84,15 +183,59 @@ impl<T: SecretGetter> Server<T> {
         req.headers_mut().insert("host", host.parse().unwrap());
 
         let declared_signature = &info.signature;
-        let actual_signature = match signer.get_signature(&to_sign) {
-            Ok(a) => a,
-            Err(e) => return Ok(internal_server(e)),
+
+        // The HMAC flavor shares `secret` between gateway and client; the
+        // RSA flavor instead verifies against the public half of a key pair
+        // whose private half never touches the gateway.
+        let (signatures_match, actual_signature) = if to_sign.algorithm == ALGORITHM_RSA {
+            let string_to_sign = match signer.string_to_sign(&to_sign) {
+                Ok(a) => a,
+                Err(e) => return Ok(internal_server(e)),
+            };
+            let signature = match parse_rsa_signature(declared_signature) {
+                Ok(a) => a,
+                Err(_) => return Ok(bad_request()),
+            };
+            let matches = match verify_rsa_signature(&secret, &string_to_sign, &signature) {
+                Ok(a) => a,
+                Err(e) => return Ok(internal_server(e)),
+            };
+            (matches, declared_signature.clone())
+        } else {
+            let actual_signature = match signer.get_signature(&to_sign) {
+                Ok(a) => a,
+                Err(e) => return Ok(internal_server(e)),
+            };
+            let matches = declared_signature
+                .as_bytes()
+                .ct_eq(actual_signature.as_bytes())
+                .into();
+            (matches, actual_signature)
         };
 
-        if declared_signature != &actual_signature {
+        if !signatures_match {
             return Ok(bad_request());
         }
 
+        if info.streaming {
+            // Chunk signatures are HMACed with the same shared secret used
+            // for the seed signature. In RSA mode `secret` is the *public*
+            // key handed out by `SecretGetter`, so anyone holding it could
+            // forge valid chunk signatures — streaming is only safe to
+            // combine with the symmetric HMAC flavor.
+            if to_sign.algorithm == ALGORITHM_RSA {
+                return Ok(bad_request());
+            }
+
+            let key = match signing_key(&to_sign.date, &secret) {
+                Ok(a) => a,
+                Err(e) => return Ok(internal_server(e)),
+            };
+            let (parts, body) = req.into_parts();
+            let verified = ChunkedSignedBody::new(body, key, to_sign.date, actual_signature);
+            req = Request::from_parts(parts, Body::wrap_stream(verified));
+        }
+
         let https = HttpsConnector::new();
         let client = hyper::Client::builder().build::<_, Body>(https);
         match client.request(req).await {
@@ -101,3 +244,74 @@ impl<T: SecretGetter> Server<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn rejects_expires_beyond_max() {
+        let now = datetime!(2024-01-01 00:00:00);
+        assert!(!validity_window_ok(
+            &now,
+            120,
+            now,
+            Duration::seconds(0),
+            60,
+        ));
+    }
+
+    #[test]
+    fn rejects_date_too_far_in_the_future() {
+        let now = datetime!(2024-01-01 00:00:00);
+        let date = now + Duration::seconds(30);
+        assert!(!validity_window_ok(
+            &date,
+            60,
+            now,
+            Duration::seconds(10),
+            60,
+        ));
+    }
+
+    #[test]
+    fn accepts_date_within_clock_skew() {
+        let now = datetime!(2024-01-01 00:00:00);
+        let date = now + Duration::seconds(5);
+        assert!(validity_window_ok(
+            &date,
+            60,
+            now,
+            Duration::seconds(10),
+            60,
+        ));
+    }
+
+    #[test]
+    fn rejects_url_past_its_expiry() {
+        let now = datetime!(2024-01-01 00:00:00);
+        let date = now - Duration::seconds(120);
+        assert!(!validity_window_ok(
+            &date,
+            60,
+            now,
+            Duration::seconds(0),
+            3600,
+        ));
+    }
+
+    #[test]
+    fn accepts_url_still_within_its_expiry() {
+        let now = datetime!(2024-01-01 00:00:00);
+        let date = now - Duration::seconds(30);
+        assert!(validity_window_ok(
+            &date,
+            60,
+            now,
+            Duration::seconds(0),
+            3600,
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+use std::pin::Pin;
+use std::str;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use futures_util::stream::Stream;
+use hyper::body::{Body, HttpBody};
+use subtle::ConstantTimeEq;
+use time::PrimitiveDateTime;
+
+use super::signing_functions::{chunk_string_to_sign, sign_chunk};
+
+/// Chunks larger than this are rejected outright rather than buffered — an
+/// unbounded declared chunk size would let a client force the gateway to
+/// buffer the whole body in one shot anyway, defeating the point of
+/// streaming verification.
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+enum ChunkState {
+    ReadingHeader,
+    ReadingData {
+        remaining: usize,
+        claimed_signature: String,
+    },
+    ReadingTrailer,
+    Done,
+}
+
+/// Verifies a `STREAMING-SUP1-HMAC-SHA256-PAYLOAD` body chunk-by-chunk as it
+/// is forwarded upstream, so the gateway never has to buffer the whole body
+/// in memory regardless of its size. Each chunk is framed as
+/// `<hex-chunk-length>;chunk-signature=<hex>\r\n<chunk-bytes>\r\n`; the
+/// signature chains from the seed signature through every chunk, and a
+/// zero-length chunk terminates the stream.
+pub struct ChunkedSignedBody {
+    inner: Body,
+    buffer: BytesMut,
+    signing_key: Vec<u8>,
+    datetime: PrimitiveDateTime,
+    previous_signature: String,
+    state: ChunkState,
+}
+
+impl ChunkedSignedBody {
+    pub fn new(
+        inner: Body,
+        signing_key: Vec<u8>,
+        datetime: PrimitiveDateTime,
+        seed_signature: String,
+    ) -> Self {
+        Self {
+            inner,
+            buffer: BytesMut::new(),
+            signing_key,
+            datetime,
+            previous_signature: seed_signature,
+            state: ChunkState::ReadingHeader,
+        }
+    }
+
+    fn take_line(&mut self) -> Option<Bytes> {
+        let pos = self.buffer.windows(2).position(|w| w == b"\r\n")?;
+        let line = self.buffer.split_to(pos).freeze();
+        let _ = self.buffer.split_to(2);
+        Some(line)
+    }
+
+    fn parse_header(line: &[u8]) -> Result<ChunkState> {
+        let line = str::from_utf8(line)?;
+        let (len, sig) = line
+            .split_once(';')
+            .ok_or_else(|| anyhow!("malformed chunk header: {line}"))?;
+        let remaining = usize::from_str_radix(len.trim(), 16)?;
+        if remaining > MAX_CHUNK_SIZE {
+            return Err(anyhow!(
+                "chunk size {remaining} exceeds maximum of {MAX_CHUNK_SIZE}"
+            ));
+        }
+        let claimed_signature = sig
+            .trim()
+            .strip_prefix("chunk-signature=")
+            .ok_or_else(|| anyhow!("malformed chunk header: {line}"))?
+            .to_string();
+        Ok(ChunkState::ReadingData {
+            remaining,
+            claimed_signature,
+        })
+    }
+
+    fn poll_more(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(bytes))) => {
+                self.buffer.extend_from_slice(&bytes);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Err(anyhow!(e))),
+            Poll::Ready(None) => Poll::Ready(Err(anyhow!("connection closed mid chunked body"))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Stream for ChunkedSignedBody {
+    type Item = Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match &this.state {
+                ChunkState::Done => return Poll::Ready(None),
+                ChunkState::ReadingHeader => match this.take_line() {
+                    Some(line) => match Self::parse_header(&line) {
+                        Ok(state) => this.state = state,
+                        Err(e) => return Poll::Ready(Some(Err(e))),
+                    },
+                    None => match this.poll_more(cx) {
+                        Poll::Ready(Ok(())) => {}
+                        Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                        Poll::Pending => return Poll::Pending,
+                    },
+                },
+                ChunkState::ReadingData { remaining, .. } => {
+                    if this.buffer.len() < *remaining {
+                        match this.poll_more(cx) {
+                            Poll::Ready(Ok(())) => continue,
+                            Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                            Poll::Pending => return Poll::Pending,
+                        }
+                    }
+
+                    let ChunkState::ReadingData {
+                        remaining,
+                        claimed_signature,
+                    } = std::mem::replace(&mut this.state, ChunkState::ReadingTrailer)
+                    else {
+                        unreachable!()
+                    };
+
+                    let chunk = this.buffer.split_to(remaining).freeze();
+                    let to_sign =
+                        chunk_string_to_sign(&this.datetime, &this.previous_signature, &chunk);
+                    let expected = match sign_chunk(&this.signing_key, &to_sign) {
+                        Ok(s) => s,
+                        Err(e) => return Poll::Ready(Some(Err(e))),
+                    };
+                    let matches: bool = expected
+                        .as_bytes()
+                        .ct_eq(claimed_signature.as_bytes())
+                        .into();
+                    if !matches {
+                        return Poll::Ready(Some(Err(anyhow!("chunk signature mismatch"))));
+                    }
+                    this.previous_signature = claimed_signature;
+
+                    if chunk.is_empty() {
+                        this.state = ChunkState::Done;
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                ChunkState::ReadingTrailer => match this.take_line() {
+                    Some(_) => this.state = ChunkState::ReadingHeader,
+                    None => match this.poll_more(cx) {
+                        Poll::Ready(Ok(())) => {}
+                        Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                        Poll::Pending => return Poll::Pending,
+                    },
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::signing::signing_functions::signing_key;
+
+    fn frame(key: &[u8], datetime: &PrimitiveDateTime, previous: &str, chunk: &[u8]) -> (String, Vec<u8>) {
+        let to_sign = chunk_string_to_sign(datetime, previous, chunk);
+        let signature = sign_chunk(key, &to_sign).unwrap();
+        let mut bytes = format!("{:x};chunk-signature={signature}\r\n", chunk.len()).into_bytes();
+        bytes.extend_from_slice(chunk);
+        bytes.extend_from_slice(b"\r\n");
+        (signature, bytes)
+    }
+
+    #[tokio::test]
+    async fn forwards_chained_chunks_and_stops_at_the_terminator() {
+        let date = datetime!(2024-01-01 00:00:00);
+        let key = signing_key(&date, "secret").unwrap();
+
+        let (sig1, frame1) = frame(&key, &date, "seed", b"hello ");
+        let (sig2, frame2) = frame(&key, &date, &sig1, b"world");
+        let (_, frame3) = frame(&key, &date, &sig2, b"");
+
+        let mut body = frame1;
+        body.extend_from_slice(&frame2);
+        body.extend_from_slice(&frame3);
+
+        let mut stream =
+            ChunkedSignedBody::new(Body::from(body), key, date, "seed".to_string());
+
+        let mut collected = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            collected.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(collected, b"hello world".as_slice());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_forged_chunk_signature() {
+        let date = datetime!(2024-01-01 00:00:00);
+        let key = signing_key(&date, "secret").unwrap();
+
+        let (_, mut body) = frame(&key, &date, "seed", b"hello");
+        let pos = body.iter().position(|&b| b == b'=').unwrap() + 1;
+        body[pos] = if body[pos] == b'0' { b'1' } else { b'0' };
+
+        let mut stream =
+            ChunkedSignedBody::new(Body::from(body), key, date, "seed".to_string());
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_stream_truncated_mid_chunk() {
+        let date = datetime!(2024-01-01 00:00:00);
+        let key = signing_key(&date, "secret").unwrap();
+
+        let (_, mut body) = frame(&key, &date, "seed", b"hello world");
+        body.truncate(body.len() - 5);
+
+        let mut stream =
+            ChunkedSignedBody::new(Body::from(body), key, date, "seed".to_string());
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_declared_chunk_size_over_the_maximum() {
+        let date = datetime!(2024-01-01 00:00:00);
+        let key = signing_key(&date, "secret").unwrap();
+
+        let header = format!("{:x};chunk-signature=deadbeef\r\n", MAX_CHUNK_SIZE + 1);
+
+        let mut stream = ChunkedSignedBody::new(
+            Body::from(header.into_bytes()),
+            key,
+            date,
+            "seed".to_string(),
+        );
+        assert!(stream.next().await.unwrap().is_err());
+    }
+}
@@ -6,7 +6,11 @@ use anyhow::{anyhow, Result};
 use hmac::{Hmac, Mac};
 use hyper::HeaderMap;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::RsaPublicKey;
 use sha2::{Digest, Sha256};
+use signature::Verifier;
 use time::{macros::format_description, PrimitiveDateTime};
 use url::Url;
 
@@ -55,6 +59,10 @@ const FRAGMENT_SLASH: &AsciiSet = &FRAGMENT.add(b'/');
 
 pub const X_ALGORITHM: &str = "X-Sup-Algorithm";
 const ALGORITHM: &str = "SUP1-HMAC-SHA256";
+// Asymmetric alternative to the shared-secret HMAC flavor: the client signs
+// with an RSA private key and the gateway verifies with the public half
+// returned by `SecretGetter`, so the private key never touches the gateway.
+pub const ALGORITHM_RSA: &str = "SUP1-RSA-SHA256";
 pub const X_CREDENTIAL: &str = "X-Sup-Credential";
 pub const X_DATE: &str = "X-Sup-Date";
 pub const X_EXPIRES: &str = "X-Sup-Expires";
@@ -63,6 +71,19 @@ pub const X_SIGNED_BODY: &str = "X-Sup-Body";
 pub const X_PROXY: &str = "X-Sup-Proxy";
 pub const X_SIGNATURE: &str = "X-Sup-Signature";
 
+// Carries a SHA-256 digest of the body so it can be folded into the
+// canonical request without requiring the body itself to be buffered as a
+// UTF-8 string. `UNSIGNED_PAYLOAD` opts out of body integrity checking
+// while still signing method/uri/query/headers.
+pub const X_CONTENT_SHA256: &str = "X-Sup-Content-Sha256";
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// Header-based flavor: everything that would otherwise travel as
+// `X-Sup-*` query params is carried in a single `Authorization` header
+// instead, for clients (e.g. fixed webhook endpoints) that can't rewrite
+// the URL they're called on.
+pub const X_AUTHORIZATION: &str = "Authorization";
+
 pub fn canonical_uri_string(uri: &Url) -> String {
     let decoded = percent_encoding::percent_decode_str(uri.path()).decode_utf8_lossy();
     utf8_percent_encode(&decoded, FRAGMENT).to_string()
@@ -87,32 +108,41 @@ pub fn canonical_query_string(uri: &Url) -> String {
     params.join("&")
 }
 
-pub fn canonical_header_string(headers: &HeaderMap) -> String {
-    let mut keyvalues = headers
-        .iter()
-        .map(|(key, value)| key.as_str().to_lowercase() + ":" + value.to_str().unwrap().trim())
-        .collect::<Vec<String>>();
+// Only the headers the client committed to in `X-Sup-SignedHeaders` may
+// take part in canonicalization; an attacker-added header must not be able
+// to silently change the canonical request.
+pub fn canonical_header_string(headers: &HeaderMap, signed_headers: &[String]) -> Result<String> {
+    let mut keyvalues = Vec::with_capacity(signed_headers.len());
+    for name in signed_headers {
+        let value = headers
+            .get(name)
+            .ok_or_else(|| anyhow!("signed header not present in request: {name}"))?;
+        keyvalues.push(format!("{name}:{}", value.to_str()?.trim()));
+    }
     keyvalues.sort();
-    keyvalues.join("\n")
+    Ok(keyvalues.join("\n"))
 }
 
-pub fn signed_header_string(headers: &HeaderMap) -> String {
-    let mut keys = headers
-        .keys()
-        .map(|key| key.as_str().to_lowercase())
-        .collect::<Vec<String>>();
+pub fn signed_header_string(signed_headers: &[String]) -> String {
+    let mut keys = signed_headers.to_vec();
     keys.sort();
     keys.join(";")
 }
 
-pub fn canonical_request(method: &str, url: &Url, headers: &HeaderMap, body: &str) -> String {
-    format!(
+pub fn canonical_request(
+    method: &str,
+    url: &Url,
+    headers: &HeaderMap,
+    signed_headers: &[String],
+    body: &str,
+) -> Result<String> {
+    Ok(format!(
         "{method}\n{uri}\n{query_string}\n{headers}\n\n{signed}\n{body}",
         uri = canonical_uri_string(url),
         query_string = canonical_query_string(url),
-        headers = canonical_header_string(headers),
-        signed = signed_header_string(headers),
-    )
+        headers = canonical_header_string(headers, signed_headers)?,
+        signed = signed_header_string(signed_headers),
+    ))
 }
 
 pub fn scope_string(datetime: &PrimitiveDateTime) -> String {
@@ -137,6 +167,65 @@ pub fn signing_key(datetime: &PrimitiveDateTime, secret_key: &str) -> Result<Vec
     Ok(date_hmac.finalize().into_bytes().to_vec())
 }
 
+// Literal payload-hash placeholder used in place of a real body hash when
+// the client streams the body as signed chunks instead of signing it whole.
+pub const STREAMING_PAYLOAD: &str = "STREAMING-SUP1-HMAC-SHA256-PAYLOAD";
+
+const CHUNK_ALGORITHM: &str = "SUP1-HMAC-SHA256-PAYLOAD";
+
+/// String-to-sign for a single chunk of a streaming body, chaining from the
+/// previous chunk's signature (the seed signature for the first chunk).
+pub fn chunk_string_to_sign(
+    datetime: &PrimitiveDateTime,
+    previous_signature: &str,
+    chunk: &[u8],
+) -> String {
+    let empty_hash = hex::encode(Sha256::digest(b""));
+    let mut hasher = Sha256::default();
+    hasher.update(chunk);
+    format!(
+        "{CHUNK_ALGORITHM}\n{timestamp}\n{scope}\n{previous_signature}\n{empty_hash}\n{chunk_hash}",
+        timestamp = datetime.format(LONG_DATETIME).unwrap(),
+        scope = scope_string(datetime),
+        chunk_hash = hex::encode(hasher.finalize().as_slice())
+    )
+}
+
+pub fn sign_chunk(signing_key: &[u8], string_to_sign: &str) -> Result<String> {
+    let mut mac = HmacSha256::new_from_slice(signing_key)?;
+    mac.update(string_to_sign.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+pub fn content_sha256_hex(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// Decodes the client-declared `SUP1-RSA-SHA256` signature. Kept separate
+/// from `verify_rsa_signature` so callers can tell a malformed, fully
+/// attacker-controlled signature apart from a genuine server-side error
+/// (e.g. a bad public key) and respond accordingly.
+pub fn parse_rsa_signature(signature_hex: &str) -> Result<Signature> {
+    let signature_bytes = hex::decode(signature_hex)?;
+    Signature::try_from(signature_bytes.as_slice())
+        .map_err(|e| anyhow!("invalid signature encoding: {e}"))
+}
+
+/// Verifies an already-decoded `SUP1-RSA-SHA256` signature over
+/// `string_to_sign` against a PEM/SPKI-encoded RSA public key, returning
+/// whether it matches.
+pub fn verify_rsa_signature(
+    public_key_pem: &str,
+    string_to_sign: &str,
+    signature: &Signature,
+) -> Result<bool> {
+    let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+        .map_err(|e| anyhow!("invalid public key: {e}"))?;
+    let verifying_key = VerifyingKey::<Sha256>::new(public_key);
+
+    Ok(verifying_key.verify(string_to_sign.as_bytes(), signature).is_ok())
+}
+
 pub fn authorization_query_params_no_sig(
     access_key: &str,
     datetime: &PrimitiveDateTime,
@@ -180,6 +269,60 @@ pub fn authorization_query_params_no_sig(
     ))
 }
 
+/// Builds the `Authorization` header value up to (but not including) the
+/// trailing `Signature=...`, mirroring `authorization_query_params_no_sig`
+/// for the header-based flavor. The caller signs the resulting canonical
+/// request and appends `, Signature={signature}` itself.
+pub fn authorization_header_no_sig(
+    access_key: &str,
+    datetime: &PrimitiveDateTime,
+    custom_headers: Option<&HeaderMap>,
+) -> Result<String> {
+    let credentials = format!("{}/{}", access_key, scope_string(datetime));
+
+    let mut signed_headers = vec![];
+    if let Some(custom_headers) = &custom_headers {
+        for k in custom_headers.keys() {
+            signed_headers.push(k.to_string())
+        }
+    }
+    let signed_headers = signed_headers.join(";");
+
+    Ok(format!(
+        "{ALGORITHM} Credential={credentials}, SignedHeaders={signed_headers}"
+    ))
+}
+
+/// Parses an `Authorization: SUP1-HMAC-SHA256 Credential=...,SignedHeaders=...,Signature=...`
+/// header value into its `(credential, signed_headers, signature)` parts.
+pub fn parse_authorization_header(value: &str) -> Result<(String, String, String)> {
+    let value = value
+        .strip_prefix(ALGORITHM)
+        .ok_or_else(|| anyhow!("unsupported authorization scheme"))?
+        .trim();
+
+    let mut credential = None;
+    let mut signed_headers = None;
+    let mut signature = None;
+
+    for part in value.split(',') {
+        let part = part.trim();
+        if let Some(v) = part.strip_prefix("Credential=") {
+            credential = Some(v.to_string());
+        } else if let Some(v) = part.strip_prefix("SignedHeaders=") {
+            signed_headers = Some(v.to_string());
+        } else if let Some(v) = part.strip_prefix("Signature=") {
+            signature = Some(v.to_string());
+        }
+    }
+
+    Ok((
+        credential.ok_or_else(|| anyhow!("missing Credential in authorization header"))?,
+        signed_headers.ok_or_else(|| anyhow!("missing SignedHeaders in authorization header"))?,
+        signature.ok_or_else(|| anyhow!("missing Signature in authorization header"))?,
+    ))
+}
+
 pub fn flatten_queries(queries: Option<&HashMap<String, String>>) -> String {
     match queries {
         None => String::new(),
@@ -198,3 +341,191 @@ pub fn flatten_queries(queries: Option<&HashMap<String, String>>) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_header_string_only_includes_declared_signed_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "example.com".parse().unwrap());
+        headers.insert("x-sup-date", "20240101T000000Z".parse().unwrap());
+        headers.insert("x-injected", "evil".parse().unwrap());
+
+        let signed_headers = vec!["host".to_string(), "x-sup-date".to_string()];
+        let canonical = canonical_header_string(&headers, &signed_headers).unwrap();
+
+        assert_eq!(
+            canonical,
+            "host:example.com\nx-sup-date:20240101T000000Z"
+        );
+    }
+
+    #[test]
+    fn canonical_header_string_rejects_a_declared_header_missing_from_the_request() {
+        let headers = HeaderMap::new();
+        let signed_headers = vec!["host".to_string()];
+        assert!(canonical_header_string(&headers, &signed_headers).is_err());
+    }
+
+    #[test]
+    fn signed_header_string_sorts_the_declared_headers() {
+        let signed_headers = vec!["x-sup-date".to_string(), "host".to_string()];
+        assert_eq!(signed_header_string(&signed_headers), "host;x-sup-date");
+    }
+
+    #[test]
+    fn content_sha256_hex_matches_a_known_vector() {
+        // sha256("") — the empty-body case is the one most likely to be
+        // off-by-one in a hand-rolled digest call.
+        assert_eq!(
+            content_sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn content_sha256_hex_differs_for_different_bodies() {
+        assert_ne!(content_sha256_hex(b"hello"), content_sha256_hex(b"world"));
+    }
+
+    #[test]
+    fn content_sha256_hex_is_deterministic() {
+        assert_eq!(content_sha256_hex(b"hello"), content_sha256_hex(b"hello"));
+    }
+
+    #[test]
+    fn verifies_a_valid_rsa_signature_and_rejects_a_mismatched_key() {
+        use rsa::pkcs1v15::SigningKey;
+        use rsa::pkcs8::{EncodePublicKey, LineEnding};
+        use rsa::RsaPrivateKey;
+        use signature::Signer;
+
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let public_key_pem = RsaPublicKey::from(&private_key)
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+
+        let signing_key = SigningKey::<Sha256>::new(private_key);
+        let signature: Signature = signing_key.sign(b"string-to-sign");
+
+        assert!(verify_rsa_signature(&public_key_pem, "string-to-sign", &signature).unwrap());
+
+        let other_private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let other_public_key_pem = RsaPublicKey::from(&other_private_key)
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        assert!(!verify_rsa_signature(&other_public_key_pem, "string-to-sign", &signature).unwrap());
+    }
+
+    #[test]
+    fn rejects_a_signature_over_tampered_data() {
+        use rsa::pkcs1v15::SigningKey;
+        use rsa::pkcs8::{EncodePublicKey, LineEnding};
+        use rsa::RsaPrivateKey;
+        use signature::Signer;
+
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let public_key_pem = RsaPublicKey::from(&private_key)
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+
+        let signing_key = SigningKey::<Sha256>::new(private_key);
+        let signature: Signature = signing_key.sign(b"original string-to-sign");
+
+        assert!(!verify_rsa_signature(&public_key_pem, "tampered string-to-sign", &signature).unwrap());
+    }
+
+    #[test]
+    fn verify_rsa_signature_rejects_an_invalid_public_key_pem() {
+        use rsa::pkcs1v15::SigningKey;
+        use rsa::RsaPrivateKey;
+        use signature::Signer;
+
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let signing_key = SigningKey::<Sha256>::new(private_key);
+        let signature: Signature = signing_key.sign(b"string-to-sign");
+
+        assert!(verify_rsa_signature("not a pem", "string-to-sign", &signature).is_err());
+    }
+
+    #[test]
+    fn parse_rsa_signature_rejects_non_hex_input() {
+        assert!(parse_rsa_signature("not hex!!").is_err());
+    }
+
+    #[test]
+    fn parse_rsa_signature_rejects_hex_that_is_not_a_valid_signature() {
+        assert!(parse_rsa_signature("deadbeef").is_err());
+    }
+
+    #[test]
+    fn parses_authorization_header_parts() {
+        let header = "SUP1-HMAC-SHA256 Credential=abc/20240101,SignedHeaders=host;x-sup-date,Signature=deadbeef";
+        let (credential, signed_headers, signature) =
+            parse_authorization_header(header).unwrap();
+        assert_eq!(credential, "abc/20240101");
+        assert_eq!(signed_headers, "host;x-sup-date");
+        assert_eq!(signature, "deadbeef");
+    }
+
+    #[test]
+    fn parses_authorization_header_with_extra_whitespace() {
+        let header = "SUP1-HMAC-SHA256  Credential=abc/20240101, SignedHeaders=host, Signature=deadbeef";
+        let (credential, signed_headers, signature) =
+            parse_authorization_header(header).unwrap();
+        assert_eq!(credential, "abc/20240101");
+        assert_eq!(signed_headers, "host");
+        assert_eq!(signature, "deadbeef");
+    }
+
+    #[test]
+    fn rejects_unsupported_scheme() {
+        assert!(parse_authorization_header(
+            "SUP1-RSA-SHA256 Credential=abc/20240101,SignedHeaders=host,Signature=deadbeef"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_missing_credential() {
+        assert!(
+            parse_authorization_header("SUP1-HMAC-SHA256 SignedHeaders=host,Signature=deadbeef")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_missing_signed_headers() {
+        assert!(
+            parse_authorization_header("SUP1-HMAC-SHA256 Credential=abc/20240101,Signature=deadbeef")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_missing_signature() {
+        assert!(parse_authorization_header(
+            "SUP1-HMAC-SHA256 Credential=abc/20240101,SignedHeaders=host"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn builds_authorization_header_without_signature() {
+        let date = PrimitiveDateTime::parse("20240101T000000Z", LONG_DATETIME).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "example.com".parse().unwrap());
+
+        let header = authorization_header_no_sig("abc", &date, Some(&headers)).unwrap();
+        assert_eq!(
+            header,
+            "SUP1-HMAC-SHA256 Credential=abc/20240101, SignedHeaders=host"
+        );
+    }
+}
+